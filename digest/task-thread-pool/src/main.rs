@@ -1,8 +1,9 @@
 use futures::{
     prelude::*, 
     executor::{self as exec,ThreadPool, ThreadPoolBuilder},
-    channel::mpsc, 
-    stream::{self,FuturesUnordered}
+    channel::mpsc,
+    stream::{self,FuturesUnordered},
+    future
 };
 use std::{
     fmt,
@@ -10,101 +11,181 @@ use std::{
     task::{ Context,Poll}
 };
 use fnv::FnvHashMap;
+use libp2p_core::PeerId;
 
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TaskId(pub usize);
 
+/// Commands that the manager can send to a still-pending connection.
 #[derive(Debug)]
-pub enum Command<T> {
+pub enum PendingCommand {
+    /// Abort the connection attempt.
+    Close,
+}
+
+/// Commands that the manager can send to an established connection.
+#[derive(Debug)]
+pub enum EstablishedCommand<T> {
     /// Notify the connection handler of an event.
     NotifyHandler(T),
+    /// Gracefully close the connection.
+    Close,
 }
 
 
 /// Events that a task can emit to its manager.
 #[derive(Debug)]
-pub enum Event {
+pub enum Event<O> {
     /// A connection to a node has succeeded.
-    Established { id: TaskId },
+    Established { id: TaskId, info: PeerId },
     /// An established connection produced an error.
     Error { id: TaskId },
     /// A pending connection failed.
-    Failed { id: TaskId },
+    Failed { id: TaskId, aborted: bool },
     /// Notify the manager of an event from the connection.
-    Notify { id: TaskId }
+    Notify { id: TaskId, output: O },
+    /// A connection has finished closing down, after the manager asked it
+    /// to close or dropped it.
+    Closed { id: TaskId }
+}
+
+/// Event generated by the `Manager` for its owner, derived from the
+/// raw `Event`s reported by the individual `Task`s.
+#[derive(Debug)]
+pub enum ManagerEvent<O> {
+    /// A pending connection has become established.
+    Connected { id: TaskId, info: PeerId },
+    /// An established connection produced an error.
+    ConnectionError { id: TaskId },
+    /// A pending connection failed. `aborted` is `true` if this was the
+    /// result of `Manager::abort_pending` rather than the connection
+    /// attempt itself failing.
+    PendingFailed { id: TaskId, aborted: bool },
+    /// An established connection notified the manager of an event.
+    Notify { id: TaskId, output: O },
 }
 
 
 
 /// The state associated with the `Task` of a connection.
 
-impl Event {
+impl<O> Event<O> {
     pub fn id(&self) -> &TaskId {
         match self {
-            Event::Established { id } => id,
+            Event::Established { id, .. } => id,
             Event::Error { id } => id,
-            Event::Notify { id } => id,
-            Event::Failed { id } => id,
+            Event::Notify { id, .. } => id,
+            Event::Failed { id, .. } => id,
+            Event::Closed { id } => id,
         }
     }
 }
 
-pub struct Task<I, F>
+/// The handler of an established connection, owned by its `Task`.
+///
+/// The manager feeds commands into the handler via `inject_command` and
+/// polls it for events to report back via `poll`.
+pub trait ConnectionHandler {
+    /// The type of commands the manager sends to this handler.
+    type InEvent;
+    /// The type of events this handler produces for the manager.
+    type OutEvent;
+
+    /// Informs the handler of a command sent by the manager.
+    fn inject_command(&mut self, cmd: Self::InEvent);
+
+    /// Polls the handler for an event to emit to the manager.
+    fn poll(&mut self, cx: &mut Context) -> Poll<Self::OutEvent>;
+
+    /// Returns a future that gracefully closes the connection, to be driven
+    /// to completion once the manager asks for the task to end.
+    fn close(&mut self) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+pub struct Task<I, F, O>
 {
     /// The ID of this task.
     id: TaskId,
 
     /// Sender to emit events to the manager of this task.
-    events: mpsc::Sender<Event>,
+    events: mpsc::Sender<Event<O>>,
 
-    /// Receiver for commands sent by the manager of this task.
-    commands: stream::Fuse<mpsc::Receiver<Command<I>>>,
+    /// Receiver for commands sent by the manager while this task is pending.
+    pending_commands: stream::Fuse<mpsc::Receiver<PendingCommand>>,
+
+    /// Receiver for commands sent by the manager once this task is established.
+    established_commands: stream::Fuse<mpsc::Receiver<EstablishedCommand<I>>>,
 
     /// Inner state of this `Task`.
-    state: State<F>,
+    state: State<F, I, O>,
 }
 
-impl<I, F> Task<I,F>
+impl<I, F, O> Task<I, F, O>
 {
     pub fn pending(
         id: TaskId,
-        events: mpsc::Sender<Event>,
-        commands: mpsc::Receiver<Command<I>>,
+        events: mpsc::Sender<Event<O>>,
+        pending_commands: mpsc::Receiver<PendingCommand>,
+        established_commands: mpsc::Receiver<EstablishedCommand<I>>,
         future: F,
+        handler: Box<dyn ConnectionHandler<InEvent = I, OutEvent = O> + Send>,
     ) -> Self {
         Task {
             id,
             events,
-            commands: commands.fuse(),
+            pending_commands: pending_commands.fuse(),
+            established_commands: established_commands.fuse(),
             state: State::Pending {
                 future: Box::pin(future),
+                handler,
             },
         }
     }
 }
 
 
-enum State<F>
-{   
+enum State<F, I, O>
+{
     Pending {
-        /// The intended handler for the established connection.
-        future:Pin<Box<F>>
+        /// The future trying to establish the connection, resolving to the
+        /// remote's `PeerId` on success.
+        future: Pin<Box<F>>,
+        /// The handler for the connection, ready to take over once `future`
+        /// resolves successfully.
+        handler: Box<dyn ConnectionHandler<InEvent = I, OutEvent = O> + Send>,
     },
-    Ready {
-        /// The actual event message to send.
-        event: Event
+    /// The connection was established and is waiting to report this fact
+    /// to the manager.
+    Connecting {
+        /// The established connection's info.
+        info: PeerId,
+        /// The handler for the connection.
+        handler: Box<dyn ConnectionHandler<InEvent = I, OutEvent = O> + Send>,
+    },
+    Established {
+        /// The handler processing commands and producing events for the
+        /// established connection.
+        handler: Box<dyn ConnectionHandler<InEvent = I, OutEvent = O> + Send>,
+    },
+    /// The connection is closing down, after the manager asked for a close
+    /// or dropped the task.
+    Closing {
+        /// The future that resolves once the connection has closed.
+        future: Pin<Box<dyn Future<Output = ()> + Send>>
     },
     /// The task has finished.
     Done
 }
 
-impl<I, F> Unpin for Task<I, F>
+impl<I, F, O> Unpin for Task<I, F, O>
 {
 }
 
 
-impl<I, F> Future for Task<I, F>
+impl<I, F, O> Future for Task<I, F, O>
+where
+    F: Future<Output = Result<PeerId, ()>>,
 {
     type Output = ();
 
@@ -117,46 +198,101 @@ impl<I, F> Future for Task<I, F>
 
         'poll: loop {
             match std::mem::replace(&mut this.state, State::Done) {
-                State::Pending { mut future } => {
-                    // Check if the manager aborted this task by dropping the `commands`
-                    // channel sender side.
-                    match Stream::poll_next(Pin::new(&mut this.commands), cx) {
+                State::Pending { mut future, handler } => {
+                    // Check if the manager aborted this task by dropping the
+                    // `pending_commands` channel sender side, or by sending
+                    // an explicit `Close`.
+                    match Stream::poll_next(Pin::new(&mut this.pending_commands), cx) {
                         Poll::Pending => {},
-                        Poll::Ready(None) => return Poll::Ready(()),
-                        Poll::Ready(Some(Command::NotifyHandler(_))) => unreachable!(
-                            "Manager does not allow sending commands to pending tasks.",
-                        )
+                        Poll::Ready(None) | Poll::Ready(Some(PendingCommand::Close)) => {
+                            match this.events.poll_ready(cx) {
+                                Poll::Pending => {
+                                    self.state = State::Pending { future, handler };
+                                    return Poll::Pending
+                                }
+                                Poll::Ready(Ok(())) => {
+                                    let _ = this.events.start_send(Event::Failed { id, aborted: true });
+                                    return Poll::Ready(())
+                                }
+                                Poll::Ready(Err(_)) => return Poll::Ready(())
+                            }
+                        }
+                    }
+
+                    // Poll the future trying to establish the connection.
+                    match future.as_mut().poll(cx) {
+                        Poll::Pending => {
+                            self.state = State::Pending { future, handler };
+                            return Poll::Pending
+                        }
+                        Poll::Ready(Ok(info)) => {
+                            this.state = State::Connecting { info, handler };
+                            continue 'poll
+                        }
+                        Poll::Ready(Err(())) => {
+                            match this.events.poll_ready(cx) {
+                                Poll::Pending => {
+                                    self.state = State::Pending { future, handler };
+                                    return Poll::Pending
+                                }
+                                Poll::Ready(Ok(())) => {
+                                    let _ = this.events.start_send(Event::Failed { id, aborted: false });
+                                    return Poll::Ready(())
+                                }
+                                Poll::Ready(Err(_)) => return Poll::Ready(())
+                            }
+                        }
                     }
                 }
-                State::Ready { event } => {
-                    // Process commands received from the manager, if any.
+                State::Connecting { info, mut handler } => {
+                    // Report the now-established connection to the manager
+                    // before handing the task over to its handler.
+                    match this.events.poll_ready(cx) {
+                        Poll::Pending => {
+                            self.state = State::Connecting { info, handler };
+                            return Poll::Pending
+                        }
+                        Poll::Ready(Ok(())) => {
+                            let _ = this.events.start_send(Event::Established { id, info });
+                            this.state = State::Established { handler };
+                        }
+                        Poll::Ready(Err(_)) => return Poll::Ready(())
+                    }
+                }
+                State::Established { mut handler } => {
+                    // Feed every currently available command to the handler.
                     loop {
-                        match Stream::poll_next(Pin::new(&mut this.commands), cx) {
+                        match Stream::poll_next(Pin::new(&mut this.established_commands), cx) {
                             Poll::Pending => break,
-                            Poll::Ready(Some(Command::NotifyHandler(event))) =>break,
-                            Poll::Ready(None) =>
-                                // The manager has dropped the task, thus initiate a
-                                // graceful shutdown of the connection, if given.
-                                // if let Some(c) = connection {
-                                //     this.state = State::Closing(c.close());
-                                //    continue 'poll
-                                // } else {
-                                //     return Poll::Ready(())
-                                // }
-                                return Poll::Ready(())
+                            Poll::Ready(Some(EstablishedCommand::NotifyHandler(cmd))) => handler.inject_command(cmd),
+                            Poll::Ready(Some(EstablishedCommand::Close)) | Poll::Ready(None) => {
+                                // The manager asked for a close, or dropped the task outright;
+                                // either way, only now ask the handler for its close future
+                                // and initiate a graceful shutdown of the connection.
+                                this.state = State::Closing { future: handler.close() };
+                                continue 'poll
+                            }
                         }
                     }
-                    // Send the event to the manager.
+                    // Poll the handler for an event to report back to the manager.
+                    let output = match handler.poll(cx) {
+                        Poll::Pending => {
+                            self.state = State::Established { handler };
+                            return Poll::Pending
+                        }
+                        Poll::Ready(output) => output,
+                    };
                     match this.events.poll_ready(cx) {
                         Poll::Pending => {
-                            self.state = State::Ready { event };
+                            self.state = State::Established { handler };
                             return Poll::Pending
                         }
                         Poll::Ready(Ok(())) => {
                             // We assume that if `poll_ready` has succeeded, then sending the event
                             // will succeed as well. If it turns out that it didn't, we will detect
                             // the closing at the next loop iteration.
-                            let _ = this.events.start_send(event);
+                            let _ = this.events.start_send(Event::Notify { id, output });
+                            this.state = State::Established { handler };
                         },
                         Poll::Ready(Err(_)) => {
                             // The manager is no longer reachable, maybe due to
@@ -167,6 +303,36 @@ impl<I, F> Future for Task<I, F>
                     }
                 }
 
+                State::Closing { mut future } => {
+                    // While closing, further commands from the manager are
+                    // rejected rather than acted upon.
+                    while let Poll::Ready(Some(_)) =
+                        Stream::poll_next(Pin::new(&mut this.established_commands), cx)
+                    {}
+
+                    match future.as_mut().poll(cx) {
+                        Poll::Pending => {
+                            self.state = State::Closing { future };
+                            return Poll::Pending
+                        }
+                        Poll::Ready(()) => {
+                            // The connection is closed; report this as a final
+                            // event before the task itself ends.
+                            match this.events.poll_ready(cx) {
+                                Poll::Pending => {
+                                    self.state = State::Closing { future };
+                                    return Poll::Pending
+                                }
+                                Poll::Ready(Ok(())) => {
+                                    let _ = this.events.start_send(Event::Closed { id });
+                                    return Poll::Ready(())
+                                }
+                                Poll::Ready(Err(_)) => return Poll::Ready(())
+                            }
+                        }
+                    }
+                }
+
                 State::Done => panic!("`Task::poll()` called after completion.")
             }
         }
@@ -198,7 +364,7 @@ impl<T: ?Sized + Executor> Executor for Box<T> {
 
 
 /// A connection `Manager` orchestrates the I/O of a set of connections.
-pub struct Manager<I> {
+pub struct Manager<I, O> {
     /// The tasks of the managed connections.
     ///
     /// Each managed connection is associated with a (background) task
@@ -222,13 +388,13 @@ pub struct Manager<I> {
 
     /// Sender distributed to managed tasks for reporting events back
     /// to the manager.
-    events_tx: mpsc::Sender<Event>,
+    events_tx: mpsc::Sender<Event<O>>,
 
     /// Receiver for events reported from managed tasks.
-    events_rx: mpsc::Receiver<Event>
+    events_rx: mpsc::Receiver<Event<O>>
 }
 
-impl<I> fmt::Debug for Manager<I>
+impl<I, O> fmt::Debug for Manager<I, O>
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_map()
@@ -237,7 +403,7 @@ impl<I> fmt::Debug for Manager<I>
     }
 }
 
-impl<I> Manager<I> {
+impl<I, O> Manager<I, O> {
     /// Creates a new connection manager.
     pub fn new(executor: Option<Box<dyn Executor + Send>>) -> Self {
         let (tx, rx) = mpsc::channel(1);
@@ -251,30 +417,310 @@ impl<I> Manager<I> {
         }
     }
 
-    /// Adds to the manager a future that tries to reach a node.
+    /// Adds to the manager a future that tries to reach a node, together with
+    /// the handler that will take over once the connection is established.
     ///
     /// This method spawns a task dedicated to resolving this future and
     /// processing the node's events.
-    pub fn add_pending<F>(&mut self, future: F)
-    where 
+    pub fn add_pending<F>(
+        &mut self,
+        future: F,
+        handler: Box<dyn ConnectionHandler<InEvent = I, OutEvent = O> + Send>,
+    )
+    where
     I: Send + 'static,
-    F: Future<Output = ()> + Send + 'static,
+    O: Send + 'static,
+    F: Future<Output = Result<PeerId, ()>> + Send + 'static,
     {
         let task_id = self.next_task_id;
         self.next_task_id.0 += 1;
 
-        let (tx, rx) = mpsc::channel(4);
-        self.tasks.insert(task_id, TaskInfo { sender: tx, state: TaskState::Pending });
-
-        let task = Box::pin(Task::<I, F>::pending(task_id, self.events_tx.clone(), rx, future));
+        let (pending_tx, pending_rx) = mpsc::channel(4);
+        let (established_tx, established_rx) = mpsc::channel(4);
+        self.tasks.insert(task_id, TaskInfo {
+            pending_sender: pending_tx,
+            established_sender: established_tx,
+            state: TaskState::Pending,
+        });
+
+        let task = Box::pin(Task::<I, F, O>::pending(
+            task_id,
+            self.events_tx.clone(),
+            pending_rx,
+            established_rx,
+            future,
+            handler,
+        ));
         if let Some(executor) = &mut self.executor {
-            print!("{}",1.to_string());
             executor.exec(task);
-        } 
-        // else {
-        //     print!("{}",2.to_string());
-        //     self.local_spawns.push(task);
-        // }
+        } else {
+            self.local_spawns.push(task);
+        }
+    }
+
+    /// Polls the manager for events from the running tasks.
+    ///
+    // NOTE: The order in which events are polled is important. The local
+    // spawns (tasks not handed off to an executor) must be driven first so
+    // that they get a chance to make progress even if nobody else is
+    // polling them, before the manager looks for events reported back by
+    // any task.
+    pub fn poll(&mut self, cx: &mut Context) -> Poll<ManagerEvent<O>> {
+        // Advance the content of `local_spawns`, i.e. the tasks for which no
+        // executor was configured, until it has no more progress to offer.
+        while let Poll::Ready(Some(())) = self.local_spawns.poll_next_unpin(cx) {}
+
+        // Poll for the next event reported by a task.
+        loop {
+            let event = match self.events_rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(event)) => event,
+                Poll::Ready(None) => unreachable!("The manager always holds on to `events_tx`."),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            match event {
+                Event::Established { id, info } => {
+                    if let Some(task) = self.tasks.get_mut(&id) {
+                        task.state = TaskState::Established(info);
+                    }
+                    return Poll::Ready(ManagerEvent::Connected { id, info });
+                }
+                Event::Notify { id, output } => {
+                    return Poll::Ready(ManagerEvent::Notify { id, output });
+                }
+                Event::Error { id } => {
+                    self.tasks.remove(&id);
+                    return Poll::Ready(ManagerEvent::ConnectionError { id });
+                }
+                Event::Failed { id, aborted } => {
+                    self.tasks.remove(&id);
+                    return Poll::Ready(ManagerEvent::PendingFailed { id, aborted });
+                }
+                Event::Closed { id } => {
+                    // A purely internal bookkeeping event used to let
+                    // `shutdown` know a task has finished closing down; it
+                    // is not surfaced to the owner of the manager.
+                    self.tasks.remove(&id);
+                }
+            }
+        }
+    }
+
+    /// Initiates a graceful shutdown of the manager.
+    ///
+    /// Drops every task's command sender, which lets established tasks
+    /// close their connection before ending and aborts still-pending tasks
+    /// outright, then returns a future that resolves once every
+    /// established task has reported back that it finished closing.
+    pub fn shutdown(mut self) -> impl Future<Output = ()> {
+        let mut remaining = self.tasks.values()
+            .filter(|task| matches!(task.state, TaskState::Established(_)))
+            .count();
+        self.tasks.clear();
+
+        future::poll_fn(move |cx| {
+            while let Poll::Ready(Some(())) = self.local_spawns.poll_next_unpin(cx) {}
+
+            while remaining > 0 {
+                match self.events_rx.poll_next_unpin(cx) {
+                    Poll::Ready(Some(Event::Closed { .. })) => remaining -= 1,
+                    // A task that got established before the manager took note
+                    // of it will also close down and report `Closed`.
+                    Poll::Ready(Some(Event::Established { .. })) => remaining += 1,
+                    Poll::Ready(Some(_)) => {},
+                    Poll::Ready(None) => return Poll::Ready(()),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            Poll::Ready(())
+        })
+    }
+
+    /// Returns an iterator over the established connections, identified by
+    /// their `TaskId` and the `PeerId` of the peer they are connected to.
+    pub fn iter_established(&self) -> impl Iterator<Item = (&TaskId, &PeerId)> {
+        self.tasks.iter().filter_map(|(id, task)| match &task.state {
+            TaskState::Established(info) => Some((id, info)),
+            TaskState::Pending => None,
+        })
+    }
+
+    /// The number of connections that are still pending.
+    pub fn pending_count(&self) -> usize {
+        self.tasks.values().filter(|task| matches!(task.state, TaskState::Pending)).count()
+    }
+
+    /// The number of established connections.
+    pub fn established_count(&self) -> usize {
+        self.tasks.values().filter(|task| matches!(task.state, TaskState::Established(_))).count()
+    }
+
+    /// Aborts a still-pending connection attempt.
+    ///
+    /// Has no effect if the task is already established or no longer exists.
+    pub fn abort_pending(&mut self, id: TaskId) {
+        if let Some(task) = self.tasks.get(&id) {
+            if let TaskState::Pending = task.state {
+                // Clone the sender so there is always a guaranteed slot of
+                // capacity for the close command, even if the bounded
+                // channel is otherwise full.
+                let mut sender = task.pending_sender.clone();
+                let _ = sender.try_send(PendingCommand::Close);
+            }
+        }
+    }
+
+    /// Starts a graceful close of an established connection.
+    ///
+    /// Has no effect if the task is still pending or no longer exists.
+    pub fn start_close(&mut self, id: TaskId) {
+        if let Some(task) = self.tasks.get(&id) {
+            if let TaskState::Established(_) = task.state {
+                // Clone the sender so there is always a guaranteed slot of
+                // capacity for the close command, even if the bounded
+                // channel is otherwise full.
+                let mut sender = task.established_sender.clone();
+                let _ = sender.try_send(EstablishedCommand::Close);
+            }
+        }
+    }
+}
+
+impl<I, O> Stream for Manager<I, O> {
+    type Item = ManagerEvent<O>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Manager::poll(&mut self, cx).map(Some)
+    }
+}
+
+/// Configurable limits enforced by a `Pool` before it admits a new
+/// connection attempt into its `Manager`.
+#[derive(Debug, Clone, Default)]
+pub struct PoolLimits {
+    /// The maximum number of simultaneously pending connections.
+    pub max_pending_connections: Option<usize>,
+    /// The maximum number of simultaneously established connections.
+    pub max_established_connections: Option<usize>,
+    /// The maximum number of simultaneously established connections per peer.
+    pub max_established_per_peer: Option<usize>,
+}
+
+/// Error returned when admitting a new connection would exceed a configured
+/// `PoolLimits` bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLimit {
+    /// The configured limit that was hit.
+    pub limit: usize,
+    /// The number of connections already counting against that limit.
+    pub current: usize,
+}
+
+/// A `Pool` wraps a `Manager`, enforcing configurable connection limits
+/// before admitting new connection attempts into it. The `Manager` remains
+/// the low-level task orchestrator; the `Pool` owns admission policy and
+/// per-peer accounting on top of it.
+pub struct Pool<I, O> {
+    manager: Manager<I, O>,
+    limits: PoolLimits,
+}
+
+impl<I, O> Pool<I, O> {
+    /// Creates a new `Pool` with the given limits, wrapping a fresh `Manager`.
+    pub fn new(executor: Option<Box<dyn Executor + Send>>, limits: PoolLimits) -> Self {
+        Pool {
+            manager: Manager::new(executor),
+            limits,
+        }
+    }
+
+    /// Checks the pending- and established-connection limits shared by
+    /// outgoing and incoming connection attempts.
+    fn check_limits(&self) -> Result<(), ConnectionLimit> {
+        if let Some(limit) = self.limits.max_pending_connections {
+            let current = self.manager.pending_count();
+            if current >= limit {
+                return Err(ConnectionLimit { limit, current });
+            }
+        }
+        if let Some(limit) = self.limits.max_established_connections {
+            let current = self.manager.established_count();
+            if current >= limit {
+                return Err(ConnectionLimit { limit, current });
+            }
+        }
+        Ok(())
+    }
+
+    /// Attempts to dial a known peer, subject to the pool's limits.
+    pub fn add_outgoing<F>(
+        &mut self,
+        future: F,
+        handler: Box<dyn ConnectionHandler<InEvent = I, OutEvent = O> + Send>,
+        peer: &PeerId,
+    ) -> Result<(), ConnectionLimit>
+    where
+    I: Send + 'static,
+    O: Send + 'static,
+    F: Future<Output = Result<PeerId, ()>> + Send + 'static,
+    {
+        self.check_limits()?;
+        if let Some(limit) = self.limits.max_established_per_peer {
+            let current = self.manager.iter_established()
+                .filter(|(_, info)| *info == peer)
+                .count();
+            if current >= limit {
+                return Err(ConnectionLimit { limit, current });
+            }
+        }
+        self.manager.add_pending(future, handler);
+        Ok(())
+    }
+
+    /// Accepts an incoming connection attempt, subject to the pool's
+    /// pending- and established-connection limits. The remote's identity
+    /// isn't known until the connection is established, so the per-peer
+    /// limit can only be enforced for outgoing connections.
+    pub fn add_incoming<F>(
+        &mut self,
+        future: F,
+        handler: Box<dyn ConnectionHandler<InEvent = I, OutEvent = O> + Send>,
+    ) -> Result<(), ConnectionLimit>
+    where
+    I: Send + 'static,
+    O: Send + 'static,
+    F: Future<Output = Result<PeerId, ()>> + Send + 'static,
+    {
+        self.check_limits()?;
+        self.manager.add_pending(future, handler);
+        Ok(())
+    }
+
+    /// Polls the pool for events from the underlying manager.
+    pub fn poll(&mut self, cx: &mut Context) -> Poll<ManagerEvent<O>> {
+        self.manager.poll(cx)
+    }
+
+    /// Initiates a graceful shutdown of the pool's manager.
+    pub fn shutdown(self) -> impl Future<Output = ()> {
+        self.manager.shutdown()
+    }
+
+    /// Returns an iterator over the established connections, identified by
+    /// their `TaskId` and the `PeerId` of the peer they are connected to.
+    pub fn iter_established(&self) -> impl Iterator<Item = (&TaskId, &PeerId)> {
+        self.manager.iter_established()
+    }
+
+    /// Aborts a still-pending connection attempt.
+    pub fn abort_pending(&mut self, id: TaskId) {
+        self.manager.abort_pending(id)
+    }
+
+    /// Starts a graceful close of an established connection.
+    pub fn start_close(&mut self, id: TaskId) {
+        self.manager.start_close(id)
     }
 }
 
@@ -282,8 +728,10 @@ impl<I> Manager<I> {
 
 #[derive(Debug)]
 struct TaskInfo<I> {
-    /// Channel endpoint to send messages to the task.
-    sender: mpsc::Sender<Command<I>>,
+    /// Channel endpoint to send commands to the task while it is pending.
+    pending_sender: mpsc::Sender<PendingCommand>,
+    /// Channel endpoint to send commands to the task once it is established.
+    established_sender: mpsc::Sender<EstablishedCommand<I>>,
     /// The state of the task as seen by the `Manager`.
     state: TaskState,
 }
@@ -293,8 +741,8 @@ struct TaskInfo<I> {
 enum TaskState {
     /// The connection is being established.
     Pending,
-    /// The connection is established.
-    Established,
+    /// The connection is established with the given peer.
+    Established(PeerId),
 }
 
 
@@ -315,7 +763,7 @@ fn main() {
     .map(|tp| Box::new(PoolWrapper(tp)) as Box<_>)
     {
     Ok(executor) => { 
-       let mut manager = Manager::<usize>::new(Some(Box::new(executor)));
+       let mut manager = Manager::<usize, ()>::new(Some(Box::new(executor)));
     //    manager.add_pending(async move {
     //     println!("Hello from task");
     // });
@@ -326,7 +774,209 @@ fn main() {
     );
     },
     Err(err) => {
-        
+
+    }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+
+    struct TestHandler {
+        notified: bool,
     }
+
+    impl ConnectionHandler for TestHandler {
+        type InEvent = ();
+        type OutEvent = usize;
+
+        fn inject_command(&mut self, _cmd: ()) {}
+
+        fn poll(&mut self, _cx: &mut Context) -> Poll<usize> {
+            if !self.notified {
+                self.notified = true;
+                Poll::Ready(42)
+            } else {
+                Poll::Pending
+            }
+        }
+
+        fn close(&mut self) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+            Box::pin(future::ready(()))
+        }
+    }
+
+    fn add_test_connection(manager: &mut Manager<(), usize>) {
+        manager.add_pending(
+            future::ready(Ok(PeerId::random())),
+            Box::new(TestHandler { notified: false }),
+        );
+    }
+
+    fn poll_manager(manager: &mut Manager<(), usize>) -> Poll<ManagerEvent<usize>> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        manager.poll(&mut cx)
+    }
+
+    #[test]
+    fn abort_pending_connection() {
+        let mut manager = Manager::<(), usize>::new(None);
+        manager.add_pending(
+            future::pending::<Result<PeerId, ()>>(),
+            Box::new(TestHandler { notified: false }),
+        );
+        assert_eq!(manager.pending_count(), 1);
+
+        manager.abort_pending(TaskId(0));
+        match poll_manager(&mut manager) {
+            Poll::Ready(ManagerEvent::PendingFailed { aborted: true, .. }) => {}
+            other => panic!("expected PendingFailed {{ aborted: true, .. }}, got {:?}", other),
+        }
+        assert_eq!(manager.pending_count(), 0);
+    }
+
+    #[test]
+    fn establish_then_notify() {
+        let mut manager = Manager::<(), usize>::new(None);
+        add_test_connection(&mut manager);
+
+        match poll_manager(&mut manager) {
+            Poll::Ready(ManagerEvent::Connected { .. }) => {}
+            other => panic!("expected Connected, got {:?}", other),
+        }
+        match poll_manager(&mut manager) {
+            Poll::Ready(ManagerEvent::Notify { output: 42, .. }) => {}
+            other => panic!("expected Notify, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn establish_then_close() {
+        let mut manager = Manager::<(), usize>::new(None);
+        add_test_connection(&mut manager);
+
+        let id = match poll_manager(&mut manager) {
+            Poll::Ready(ManagerEvent::Connected { id, .. }) => id,
+            other => panic!("expected Connected, got {:?}", other),
+        };
+        let _ = poll_manager(&mut manager); // drain the queued Notify
+        assert_eq!(manager.established_count(), 1);
+
+        manager.start_close(id);
+        for _ in 0..10 {
+            if manager.established_count() == 0 {
+                return;
+            }
+            let _ = poll_manager(&mut manager);
+        }
+        panic!("start_close did not lead to the connection being closed");
+    }
+
+    #[test]
+    fn shutdown_with_established_connection() {
+        let mut manager = Manager::<(), usize>::new(None);
+        add_test_connection(&mut manager);
+
+        match poll_manager(&mut manager) {
+            Poll::Ready(ManagerEvent::Connected { .. }) => {}
+            other => panic!("expected Connected, got {:?}", other),
+        }
+        let _ = poll_manager(&mut manager); // drain the queued Notify
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut shutdown = Box::pin(manager.shutdown());
+        for _ in 0..10 {
+            if shutdown.as_mut().poll(&mut cx).is_ready() {
+                return;
+            }
+        }
+        panic!("Manager::shutdown() did not complete with an established connection present");
+    }
+
+    fn poll_pool(pool: &mut Pool<(), usize>) -> Poll<ManagerEvent<usize>> {
+        let mut cx = Context::from_waker(noop_waker_ref());
+        pool.poll(&mut cx)
+    }
+
+    fn add_pool_connection(pool: &mut Pool<(), usize>) -> Result<(), ConnectionLimit> {
+        pool.add_incoming(
+            future::ready(Ok(PeerId::random())),
+            Box::new(TestHandler { notified: false }),
+        )
+    }
+
+    #[test]
+    fn pool_add_within_limits_succeeds() {
+        let limits = PoolLimits {
+            max_pending_connections: Some(1),
+            max_established_connections: Some(1),
+            max_established_per_peer: Some(1),
+        };
+        let mut pool = Pool::<(), usize>::new(None, limits);
+        assert_eq!(add_pool_connection(&mut pool), Ok(()));
+    }
+
+    #[test]
+    fn pool_enforces_max_pending_connections() {
+        let limits = PoolLimits {
+            max_pending_connections: Some(1),
+            ..PoolLimits::default()
+        };
+        let mut pool = Pool::<(), usize>::new(None, limits);
+        assert_eq!(add_pool_connection(&mut pool), Ok(()));
+        assert_eq!(
+            add_pool_connection(&mut pool),
+            Err(ConnectionLimit { limit: 1, current: 1 })
+        );
+    }
+
+    #[test]
+    fn pool_enforces_max_established_connections() {
+        let limits = PoolLimits {
+            max_established_connections: Some(1),
+            ..PoolLimits::default()
+        };
+        let mut pool = Pool::<(), usize>::new(None, limits);
+        assert_eq!(add_pool_connection(&mut pool), Ok(()));
+        match poll_pool(&mut pool) {
+            Poll::Ready(ManagerEvent::Connected { .. }) => {}
+            other => panic!("expected Connected, got {:?}", other),
+        }
+
+        assert_eq!(
+            add_pool_connection(&mut pool),
+            Err(ConnectionLimit { limit: 1, current: 1 })
+        );
+    }
+
+    #[test]
+    fn pool_enforces_max_established_per_peer() {
+        let limits = PoolLimits {
+            max_established_per_peer: Some(1),
+            ..PoolLimits::default()
+        };
+        let mut pool = Pool::<(), usize>::new(None, limits);
+        let peer = PeerId::random();
+
+        pool.add_outgoing(
+            future::ready(Ok(peer)),
+            Box::new(TestHandler { notified: false }),
+            &peer,
+        ).expect("first outgoing connection should be admitted");
+        match poll_pool(&mut pool) {
+            Poll::Ready(ManagerEvent::Connected { .. }) => {}
+            other => panic!("expected Connected, got {:?}", other),
+        }
+
+        assert_eq!(
+            pool.add_outgoing(
+                future::ready(Ok(peer)),
+                Box::new(TestHandler { notified: false }),
+                &peer,
+            ),
+            Err(ConnectionLimit { limit: 1, current: 1 })
+        );
     }
 }